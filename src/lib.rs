@@ -5,10 +5,35 @@ use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
 use std::rc::Rc;
 
+const DEFAULT_FUZZ: usize = 2;
+
+#[derive(Clone, Copy)]
+enum NewlinePolicy {
+    Lf,
+    CrLf,
+    Preserve,
+    Auto,
+}
+
+fn parse_newline_policy(s: &str) -> Result<NewlinePolicy, &'static str> {
+    match s {
+        "lf" => Ok(NewlinePolicy::Lf),
+        "crlf" => Ok(NewlinePolicy::CrLf),
+        "preserve" => Ok(NewlinePolicy::Preserve),
+        "auto" => Ok(NewlinePolicy::Auto),
+        _ => Err("--newline must be one of lf, crlf, preserve, auto"),
+    }
+}
+
 pub struct Config {
     verbose: usize,
     directory: Option<String>,
     output: Option<String>,
+    unified: bool,
+    fuzz: usize,
+    diff_mode: bool,
+    testfile: Option<String>,
+    newline_policy: NewlinePolicy,
     webfilename: String,
     chfilenames: Vec<String>,
 }
@@ -18,6 +43,11 @@ impl Config {
         let mut directory = None;
         let mut output = None;
         let mut verbose = 0;
+        let mut unified = false;
+        let mut fuzz = DEFAULT_FUZZ;
+        let mut diff_mode = false;
+        let mut testfile = None;
+        let mut newline_policy = NewlinePolicy::Auto;
         let mut i = 1;
         while i < args.len() {
             match args[i].as_str() {
@@ -45,6 +75,40 @@ impl Config {
                         return Err("-o require output filename");
                     }
                 }
+                "-u" => {
+                    unified = true;
+                    i += 1;
+                }
+                "-F" => {
+                    if i + 1 < args.len() {
+                        fuzz = args[i + 1]
+                            .parse()
+                            .map_err(|_| "-F requires a numeric fuzz factor")?;
+                        i += 2;
+                    } else {
+                        return Err("-F require fuzz factor");
+                    }
+                }
+                "--diff" => {
+                    diff_mode = true;
+                    i += 1;
+                }
+                "--test" => {
+                    if i + 1 < args.len() {
+                        testfile = Some(args[i + 1].clone());
+                        i += 2;
+                    } else {
+                        return Err("--test require fixture filename");
+                    }
+                }
+                "--newline" => {
+                    if i + 1 < args.len() {
+                        newline_policy = parse_newline_policy(&args[i + 1])?;
+                        i += 2;
+                    } else {
+                        return Err("--newline require a policy");
+                    }
+                }
                 "--" => {
                     i += 1;
                     break;
@@ -53,6 +117,21 @@ impl Config {
             }
         }
 
+        if testfile.is_some() {
+            return Ok(Config {
+                verbose,
+                directory,
+                output,
+                unified,
+                fuzz,
+                diff_mode,
+                testfile,
+                newline_policy,
+                webfilename: String::new(),
+                chfilenames: Vec::new(),
+            });
+        }
+
         if args.len() - i < 2 {
             return Err("not enough arguments");
         }
@@ -65,6 +144,11 @@ impl Config {
             verbose,
             directory,
             output,
+            unified,
+            fuzz,
+            diff_mode,
+            testfile,
+            newline_policy,
             webfilename,
             chfilenames,
         })
@@ -72,6 +156,10 @@ impl Config {
 }
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    if let Some(testfile) = &config.testfile {
+        return run_test_file(testfile, config.verbose);
+    }
+
     if let Some(working_directory) = config.directory {
         if config.verbose > 0 {
             eprintln!("Working directory: {}", &working_directory);
@@ -82,15 +170,34 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
     if config.verbose > 0 {
         eprintln!("Web file: {}", &config.webfilename);
     }
-    let mut weblines = read_filelines(&config.webfilename)?;
+    let original_weblines = read_filelines(&config.webfilename)?;
+    let mut weblines = original_weblines.clone();
 
     for chfilename in &config.chfilenames {
         if config.verbose > 0 {
             eprintln!("Change file: {}", &chfilename);
         }
-        let chfile = read_changefile(chfilename)?;
+        let chfile = read_changefile(chfilename, config.unified)?;
 
-        weblines = apply_changefile(weblines, chfile)?;
+        weblines = apply_changefile(weblines, chfile, config.fuzz, config.verbose)?;
+    }
+
+    if config.diff_mode {
+        let diff = unified_diff(&original_weblines, &weblines, &config.webfilename);
+
+        match config.output.as_deref() {
+            None | Some("-") => {
+                std::io::stdout().write_all(&diff)?;
+            }
+            Some(output) => {
+                if config.verbose > 0 {
+                    eprintln!("Output file: {}", &output);
+                }
+                fs::write(output, &diff)?;
+            }
+        }
+
+        return Ok(());
     }
 
     match config.output.as_deref() {
@@ -98,13 +205,17 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
             print_filelines(&weblines);
         }
         Some("-") => {
-            write_filelines(std::io::stdout(), &weblines)?;
+            write_filelines(std::io::stdout(), &weblines, config.newline_policy)?;
         }
         Some(output) => {
             if config.verbose > 0 {
                 eprintln!("Output file: {}", &output);
             }
-            write_filelines(BufWriter::new(fs::File::create(output)?), &weblines)?;
+            write_filelines(
+                BufWriter::new(fs::File::create(output)?),
+                &weblines,
+                config.newline_policy,
+            )?;
         }
     }
 
@@ -112,6 +223,10 @@ pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
 }
 
 fn print_filelines(lines: &[FileLine]) {
+    print!("{}", format_filelines(lines));
+}
+
+fn format_filelines(lines: &[FileLine]) -> String {
     let max_filename = lines
         .iter()
         .fold(0, |max, line| usize::max(max, line.filename.len()));
@@ -122,21 +237,27 @@ fn print_filelines(lines: &[FileLine]) {
     );
     let width = max_filename + max_line_str + 2;
 
+    let mut out = String::new();
     for line in lines {
         let fileline = format!("{}({})", line.filename, line.line_num);
-        print!("{:width$} | ", fileline, width = width);
+        out.push_str(&format!("{:width$} | ", fileline, width = width));
         for &b in &line.contents {
             if b >= 0x20 && b <= 0x7E {
-                print!("{}", char::from(b));
+                out.push(char::from(b));
             } else {
-                print!("\x1B[7m<{:02X}>\x1B[0m", b);
+                out.push_str(&format!("\x1B[7m<{:02X}>\x1B[0m", b));
             }
         }
-        println!();
+        out.push('\n');
     }
+    out
 }
 
-fn write_filelines<W: Write>(mut w: W, lines: &[FileLine]) -> std::io::Result<()> {
+fn write_filelines<W: Write>(
+    mut w: W,
+    lines: &[FileLine],
+    newline_policy: NewlinePolicy,
+) -> std::io::Result<()> {
     let max_filename = lines
         .iter()
         .fold(0, |max, line| usize::max(max, line.filename.len()));
@@ -151,10 +272,28 @@ fn write_filelines<W: Write>(mut w: W, lines: &[FileLine]) -> std::io::Result<()
         let fileline = format!("{}({})", line.filename, line.line_num);
         write!(w, "{:width$} | ", fileline, width = width)?;
         w.write_all(&line.contents)?;
-        if cfg!(windows) {
-            w.write(&[0x0D, 0x0A])?;
-        } else {
-            w.write(&[0x0A])?;
+
+        let ending = match newline_policy {
+            NewlinePolicy::Lf => LineEnding::Lf,
+            NewlinePolicy::CrLf => LineEnding::CrLf,
+            NewlinePolicy::Preserve => line.ending,
+            NewlinePolicy::Auto => {
+                if cfg!(windows) {
+                    LineEnding::CrLf
+                } else {
+                    LineEnding::Lf
+                }
+            }
+        };
+
+        match ending {
+            LineEnding::CrLf => {
+                w.write_all(&[0x0D, 0x0A])?;
+            }
+            LineEnding::Lf => {
+                w.write_all(&[0x0A])?;
+            }
+            LineEnding::None => {}
         }
     }
     w.flush()?;
@@ -182,8 +321,34 @@ struct ChangeFileSection {
     newlines: Vec<FileLine>,
 }
 
-fn read_changefile(filename: &str) -> Result<Vec<ChangeFileSection>, Box<dyn Error>> {
-    let mut chfilelines = read_filelines(filename)?.into_iter();
+fn read_changefile(
+    filename: &str,
+    force_unified: bool,
+) -> Result<Vec<ChangeFileSection>, Box<dyn Error>> {
+    let chfilelines = read_filelines(filename)?;
+
+    select_and_parse_changefile(chfilelines, force_unified)
+}
+
+fn select_and_parse_changefile(
+    chfilelines: Vec<FileLine>,
+    force_unified: bool,
+) -> Result<Vec<ChangeFileSection>, Box<dyn Error>> {
+    let looks_unified = chfilelines
+        .iter()
+        .find(|line| !u8_slice_trim_start(&line.contents).is_empty())
+        .map(|line| line.contents.starts_with(b"---") || line.contents.starts_with(b"diff "))
+        .unwrap_or(false);
+
+    if force_unified || looks_unified {
+        parse_unified_changefile(chfilelines)
+    } else {
+        parse_web_changefile(chfilelines)
+    }
+}
+
+fn parse_web_changefile(chfilelines: Vec<FileLine>) -> Result<Vec<ChangeFileSection>, Box<dyn Error>> {
+    let mut chfilelines = chfilelines.into_iter();
 
     let mut sections = Vec::new();
     'outer: loop {
@@ -238,7 +403,7 @@ fn read_changefile(filename: &str) -> Result<Vec<ChangeFileSection>, Box<dyn Err
             let line = if let Some(line) = chfilelines.next() {
                 line
             } else {
-                eprintln!("At the end of change file missing @z [{}]", filename);
+                eprintln!("At the end of change file missing @z [{}]", headline.filename);
                 break;
             };
 
@@ -259,23 +424,145 @@ fn read_changefile(filename: &str) -> Result<Vec<ChangeFileSection>, Box<dyn Err
     Ok(sections)
 }
 
+enum HunkSide {
+    Old,
+    New,
+    Both,
+}
+
+fn parse_unified_changefile(
+    chfilelines: Vec<FileLine>,
+) -> Result<Vec<ChangeFileSection>, Box<dyn Error>> {
+    let mut chfilelines = chfilelines.into_iter().peekable();
+
+    let mut sections = Vec::new();
+    loop {
+        // skip file headers and blank lines until a hunk header `@@ ... @@` is found.
+        let headline = loop {
+            let line = if let Some(line) = chfilelines.next() {
+                line
+            } else {
+                return Ok(sections);
+            };
+
+            if line.contents.starts_with(b"@@") {
+                break line;
+            }
+
+            if line.contents.starts_with(b"---")
+                || line.contents.starts_with(b"+++")
+                || line.contents.starts_with(b"diff ")
+                || line.contents.starts_with(b"index ")
+                || u8_slice_trim_start(&line.contents).is_empty()
+            {
+                continue;
+            }
+
+            return Err(format!(
+                "Unrecognized line in unified diff at {}({})",
+                line.filename, line.line_num
+            )
+            .into());
+        };
+
+        let mut oldlines: Vec<FileLine> = Vec::new();
+        let mut newlines: Vec<FileLine> = Vec::new();
+        let mut last_side: Option<HunkSide> = None;
+
+        while let Some(peeked) = chfilelines.peek() {
+            if peeked.contents.starts_with(b"@@")
+                || peeked.contents.starts_with(b"---")
+                || peeked.contents.starts_with(b"diff ")
+            {
+                break;
+            }
+
+            let mut line = chfilelines.next().unwrap();
+
+            if line.contents.starts_with(b"\\ No newline at end of file") {
+                match last_side {
+                    Some(HunkSide::Old) | Some(HunkSide::Both) => {
+                        if let Some(last) = oldlines.last_mut() {
+                            last.ending = LineEnding::None;
+                        }
+                    }
+                    _ => {}
+                }
+                match last_side {
+                    Some(HunkSide::New) | Some(HunkSide::Both) => {
+                        if let Some(last) = newlines.last_mut() {
+                            last.ending = LineEnding::None;
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            match line.contents.first().copied() {
+                Some(b' ') => {
+                    line.contents.remove(0);
+                    oldlines.push(clone_fileline(&line));
+                    newlines.push(line);
+                    last_side = Some(HunkSide::Both);
+                }
+                Some(b'-') => {
+                    line.contents.remove(0);
+                    oldlines.push(line);
+                    last_side = Some(HunkSide::Old);
+                }
+                Some(b'+') => {
+                    line.contents.remove(0);
+                    newlines.push(line);
+                    last_side = Some(HunkSide::New);
+                }
+                _ => {
+                    return Err(format!(
+                        "Malformed hunk line at {}({})",
+                        line.filename, line.line_num
+                    )
+                    .into());
+                }
+            }
+        }
+
+        sections.push(ChangeFileSection {
+            headline,
+            oldlines,
+            newlines,
+        });
+    }
+}
+
+fn clone_fileline(line: &FileLine) -> FileLine {
+    FileLine {
+        filename: Rc::clone(&line.filename),
+        line_num: line.line_num,
+        contents: line.contents.clone(),
+        ending: line.ending,
+    }
+}
+
 fn apply_changefile(
     weblines: Vec<FileLine>,
     chfilesections: Vec<ChangeFileSection>,
+    fuzz: usize,
+    verbose: usize,
 ) -> Result<Vec<FileLine>, Box<dyn Error>> {
     let mut result = Vec::new();
     let mut weblines = VecDeque::from(weblines);
 
-    fn match_position(weblines: &VecDeque<FileLine>, oldlines: &Vec<FileLine>) -> Option<usize> {
-        if weblines.len() < oldlines.len() {
+    // Search for the run of `window` starting at or after the front of `weblines`,
+    // treating lines that differ only by ASCII whitespace as equal.
+    fn find_window(weblines: &VecDeque<FileLine>, window: &[FileLine]) -> Option<usize> {
+        if weblines.len() < window.len() {
             return None;
         }
-        for i in 0..weblines.len() {
+        for i in 0..=(weblines.len() - window.len()) {
             if weblines
-                .range(i..)
-                .take(oldlines.len())
-                .map(|l| &l.contents)
-                .eq(oldlines.iter().map(|l| &l.contents))
+                .range(i..i + window.len())
+                .zip(window.iter())
+                .all(|(a, b)| lines_fuzzy_eq(&a.contents, &b.contents))
             {
                 return Some(i);
             }
@@ -283,8 +570,49 @@ fn apply_changefile(
         None
     }
 
+    // `patch(1)`-style fuzzy match: try the full `oldlines` first, then retry with
+    // up to `fuzz` lines dropped from the trailing end, then from the leading end.
+    // Returns the position of the start of the *full* `oldlines` run (trusting any
+    // dropped context rather than re-verifying it) and the fuzz actually used.
+    fn match_position(
+        weblines: &VecDeque<FileLine>,
+        oldlines: &[FileLine],
+        fuzz: usize,
+    ) -> Option<(usize, usize)> {
+        if oldlines.is_empty() {
+            return find_window(weblines, oldlines).map(|pos| (pos, 0));
+        }
+
+        let max_drop = fuzz.min(oldlines.len() - 1);
+
+        for trail in 0..=max_drop {
+            let window = &oldlines[..oldlines.len() - trail];
+            if let Some(pos) = find_window(weblines, window) {
+                return Some((pos, trail));
+            }
+        }
+
+        for lead in 1..=max_drop {
+            let window = &oldlines[lead..];
+            if let Some(pos) = find_window(weblines, window) {
+                if let Some(start) = pos.checked_sub(lead) {
+                    return Some((start, lead));
+                }
+            }
+        }
+
+        None
+    }
+
     for mut section in chfilesections {
-        if let Some(pos) = match_position(&weblines, &section.oldlines) {
+        if let Some((pos, fuzz_used)) = match_position(&weblines, &section.oldlines, fuzz) {
+            if verbose > 0 && (pos > 0 || fuzz_used > 0) {
+                eprintln!(
+                    "Hunk applied at offset {:+} (fuzz {}) [{}({})]",
+                    pos as isize, fuzz_used, section.headline.filename, section.headline.line_num,
+                );
+            }
+
             result.reserve(pos + section.newlines.len());
             for _ in 0..pos {
                 result.push(weblines.pop_front().unwrap());
@@ -309,6 +637,214 @@ fn apply_changefile(
     Ok(result)
 }
 
+fn lines_fuzzy_eq(a: &[u8], b: &[u8]) -> bool {
+    if a == b {
+        return true;
+    }
+    a.iter()
+        .filter(|b| !b.is_ascii_whitespace())
+        .eq(b.iter().filter(|b| !b.is_ascii_whitespace()))
+}
+
+const DIFF_CONTEXT: usize = 3;
+
+enum DiffOp {
+    Equal { old: usize, new: usize },
+    Delete { old: usize },
+    Insert { new: usize },
+}
+
+// Classic Myers O(ND) greedy LCS: `v[k]` holds the furthest-reaching x on diagonal
+// `k` for the current edit distance `d`. `offset` re-centers negative diagonals
+// into the array. Returns the full trace (one `v` snapshot per `d`) together with
+// `offset`, so `myers_backtrack` can replay it to recover the edit script.
+fn myers_trace(old: &[&[u8]], new: &[&[u8]]) -> (Vec<Vec<isize>>, isize) {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = ((n + m) as usize).max(1);
+    let offset = max as isize;
+
+    let mut v = vec![0isize; 2 * max + 1];
+    let mut trace = Vec::new();
+
+    for d in 0..=max {
+        trace.push(v.clone());
+        let d = d as isize;
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[idx] = x;
+
+            if x >= n && y >= m {
+                return (trace, offset);
+            }
+
+            k += 2;
+        }
+    }
+
+    (trace, offset)
+}
+
+fn myers_backtrack(trace: &[Vec<isize>], offset: isize, n: usize, m: usize) -> Vec<DiffOp> {
+    let mut x = n as isize;
+    let mut y = m as isize;
+    let mut ops = Vec::new();
+
+    for d in (0..trace.len()).rev() {
+        let v = &trace[d];
+        let d = d as isize;
+        let k = x - y;
+        let idx = (k + offset) as usize;
+
+        let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_idx = (prev_k + offset) as usize;
+        let prev_x = v[prev_idx];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(DiffOp::Equal {
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+
+        if d > 0 {
+            if x == prev_x {
+                y -= 1;
+                ops.push(DiffOp::Insert { new: y as usize });
+            } else {
+                x -= 1;
+                ops.push(DiffOp::Delete { old: x as usize });
+            }
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    ops.reverse();
+    ops
+}
+
+fn preceding_old_line_num(ops: &[DiffOp], before: usize) -> usize {
+    for op in ops[..before].iter().rev() {
+        match *op {
+            DiffOp::Equal { old, .. } | DiffOp::Delete { old } => return old + 1,
+            DiffOp::Insert { .. } => {}
+        }
+    }
+    0
+}
+
+fn unified_diff(original: &[FileLine], modified: &[FileLine], filename: &str) -> Vec<u8> {
+    let old_bytes: Vec<&[u8]> = original.iter().map(|l| l.contents.as_slice()).collect();
+    let new_bytes: Vec<&[u8]> = modified.iter().map(|l| l.contents.as_slice()).collect();
+
+    let (trace, offset) = myers_trace(&old_bytes, &new_bytes);
+    let ops = myers_backtrack(&trace, offset, old_bytes.len(), new_bytes.len());
+
+    if ops.iter().all(|op| matches!(op, DiffOp::Equal { .. })) {
+        return Vec::new();
+    }
+
+    // maximal runs of consecutive non-equal ops.
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < ops.len() {
+        if matches!(ops[i], DiffOp::Equal { .. }) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < ops.len() && !matches!(ops[i], DiffOp::Equal { .. }) {
+            i += 1;
+        }
+        blocks.push((start, i));
+    }
+
+    // merge blocks whose surrounding context would overlap or touch.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in blocks {
+        let ctx_start = start.saturating_sub(DIFF_CONTEXT);
+        if let Some(last) = hunks.last_mut() {
+            if ctx_start <= last.1 + DIFF_CONTEXT {
+                last.1 = end;
+                continue;
+            }
+        }
+        hunks.push((start, end));
+    }
+
+    let mut out = Vec::new();
+    writeln!(out, "--- {}", filename).unwrap();
+    writeln!(out, "+++ {}", filename).unwrap();
+
+    for (start, end) in hunks {
+        let hunk_start = start.saturating_sub(DIFF_CONTEXT);
+        let hunk_end = usize::min(ops.len(), end + DIFF_CONTEXT);
+
+        let mut old_start = None;
+        let mut new_start = None;
+        let mut old_len = 0;
+        let mut new_len = 0;
+        let mut lines: Vec<(u8, Vec<u8>)> = Vec::new();
+
+        for op in &ops[hunk_start..hunk_end] {
+            match *op {
+                DiffOp::Equal { old, new } => {
+                    old_start.get_or_insert(original[old].line_num);
+                    new_start.get_or_insert(new + 1);
+                    old_len += 1;
+                    new_len += 1;
+                    lines.push((b' ', original[old].contents.clone()));
+                }
+                DiffOp::Delete { old } => {
+                    old_start.get_or_insert(original[old].line_num);
+                    old_len += 1;
+                    lines.push((b'-', original[old].contents.clone()));
+                }
+                DiffOp::Insert { new } => {
+                    new_start.get_or_insert(new + 1);
+                    new_len += 1;
+                    lines.push((b'+', modified[new].contents.clone()));
+                }
+            }
+        }
+
+        let old_start = old_start.unwrap_or_else(|| preceding_old_line_num(&ops, hunk_start));
+        let new_start = new_start.unwrap_or(0);
+
+        writeln!(out, "@@ -{},{} +{},{} @@", old_start, old_len, new_start, new_len).unwrap();
+
+        for (marker, contents) in lines {
+            out.push(marker);
+            out.extend_from_slice(&contents);
+            out.push(b'\n');
+        }
+    }
+
+    out
+}
+
 fn u8_slice_trim_start(s: &[u8]) -> &[u8] {
     let first = s
         .iter()
@@ -317,10 +853,19 @@ fn u8_slice_trim_start(s: &[u8]) -> &[u8] {
     &s[first..]
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LineEnding {
+    Lf,
+    CrLf,
+    None,
+}
+
+#[derive(Clone)]
 struct FileLine {
     filename: Rc<String>,
     line_num: usize,
     contents: Vec<u8>,
+    ending: LineEnding,
 }
 
 fn read_filelines(filename: &str) -> Result<Vec<FileLine>, Box<dyn Error>> {
@@ -337,22 +882,261 @@ fn read_filelines(filename: &str) -> Result<Vec<FileLine>, Box<dyn Error>> {
             break;
         }
 
-        // remove tail LF
-        if Some(&0x0A) == contents.last() {
+        let ending = if Some(&0x0A) == contents.last() {
             contents.pop();
 
-            // if windows, also remove CR.
-            if cfg!(windows) && Some(&0x0D) == contents.last() {
+            if Some(&0x0D) == contents.last() {
                 contents.pop();
+                LineEnding::CrLf
+            } else {
+                LineEnding::Lf
             }
-        }
+        } else {
+            LineEnding::None
+        };
 
         filelines.push(FileLine {
             filename: Rc::clone(&filename),
             line_num,
             contents,
+            ending,
         })
     }
 
     Ok(filelines)
 }
+
+fn split_into_filelines(filename: &Rc<String>, data: &[u8]) -> Vec<FileLine> {
+    let mut filelines = Vec::new();
+    let mut rest = data;
+    let mut line_num = 1;
+
+    while !rest.is_empty() {
+        let idx = rest.iter().position(|&b| b == 0x0A);
+        let (mut line, tail, mut ending) = match idx {
+            Some(i) => (&rest[..i], &rest[i + 1..], LineEnding::Lf),
+            None => (rest, &[][..], LineEnding::None),
+        };
+
+        if ending == LineEnding::Lf {
+            if let Some((&0x0D, init)) = line.split_last() {
+                line = init;
+                ending = LineEnding::CrLf;
+            }
+        }
+
+        filelines.push(FileLine {
+            filename: Rc::clone(filename),
+            line_num,
+            contents: line.to_vec(),
+            ending,
+        });
+
+        line_num += 1;
+        rest = tail;
+
+        if idx.is_none() {
+            break;
+        }
+    }
+
+    filelines
+}
+
+enum TestBlock {
+    Web(Vec<u8>),
+    Change(Vec<u8>),
+    Expect(Vec<u8>),
+}
+
+struct TestCase {
+    blocks: Vec<TestBlock>,
+    status: i32,
+    diff_mode: bool,
+    newline_policy: Option<NewlinePolicy>,
+}
+
+fn is_directive_line(contents: &[u8]) -> bool {
+    contents == b"#web"
+        || contents == b"#change"
+        || contents == b"#expect"
+        || contents == b"#nonewline"
+        || contents == b"#diff"
+        || contents.starts_with(b"#status ")
+        || contents.starts_with(b"#newline ")
+}
+
+fn collect_block(iter: &mut std::iter::Peekable<std::vec::IntoIter<FileLine>>) -> Vec<u8> {
+    let mut block = Vec::new();
+    while let Some(line) = iter.peek() {
+        if is_directive_line(&line.contents) {
+            break;
+        }
+        let line = iter.next().unwrap();
+        block.extend_from_slice(&line.contents);
+        block.push(b'\n');
+    }
+    block
+}
+
+fn parse_test_case(lines: Vec<FileLine>) -> Result<TestCase, Box<dyn Error>> {
+    let mut iter = lines.into_iter().peekable();
+    let mut blocks: Vec<TestBlock> = Vec::new();
+    let mut status = 0;
+    let mut diff_mode = false;
+    let mut newline_policy = None;
+
+    while let Some(line) = iter.next() {
+        let directive = line.contents.as_slice();
+        if directive == b"#web" {
+            blocks.push(TestBlock::Web(collect_block(&mut iter)));
+        } else if directive == b"#change" {
+            blocks.push(TestBlock::Change(collect_block(&mut iter)));
+        } else if directive == b"#expect" {
+            blocks.push(TestBlock::Expect(collect_block(&mut iter)));
+        } else if directive == b"#diff" {
+            diff_mode = true;
+        } else if directive == b"#nonewline" {
+            let data = match blocks.last_mut() {
+                Some(TestBlock::Web(d)) | Some(TestBlock::Change(d)) | Some(TestBlock::Expect(d)) => d,
+                None => {
+                    return Err(format!(
+                        "#nonewline with no preceding block at {}({})",
+                        line.filename, line.line_num
+                    )
+                    .into());
+                }
+            };
+            if data.last() == Some(&b'\n') {
+                data.pop();
+            }
+        } else if directive.starts_with(b"#status ") {
+            let num = std::str::from_utf8(&directive[b"#status ".len()..])
+                .map_err(|_| "Malformed #status directive")?;
+            status = num
+                .trim()
+                .parse()
+                .map_err(|_| "Malformed #status directive")?;
+        } else if directive.starts_with(b"#newline ") {
+            let word = std::str::from_utf8(&directive[b"#newline ".len()..])
+                .map_err(|_| "Malformed #newline directive")?;
+            newline_policy = Some(parse_newline_policy(word.trim())?);
+        } else if u8_slice_trim_start(directive).is_empty() {
+            continue;
+        } else {
+            return Err(format!(
+                "Unrecognized directive at {}({})",
+                line.filename, line.line_num
+            )
+            .into());
+        }
+    }
+
+    Ok(TestCase {
+        blocks,
+        status,
+        diff_mode,
+        newline_policy,
+    })
+}
+
+fn print_line_diff(expected: &str, actual: &str) {
+    let expected_lines: Vec<&str> = expected.split('\n').collect();
+    let actual_lines: Vec<&str> = actual.split('\n').collect();
+    let max = expected_lines.len().max(actual_lines.len());
+
+    for i in 0..max {
+        let e = expected_lines.get(i).copied().unwrap_or("<missing>");
+        let a = actual_lines.get(i).copied().unwrap_or("<missing>");
+        if e != a {
+            eprintln!("    line {}:", i + 1);
+            eprintln!("      expected: {}", e);
+            eprintln!("      actual:   {}", a);
+        }
+    }
+}
+
+fn run_test_file(path: &str, verbose: usize) -> Result<(), Box<dyn Error>> {
+    let TestCase {
+        blocks,
+        status: expected_status,
+        diff_mode,
+        newline_policy,
+    } = parse_test_case(read_filelines(path)?)?;
+
+    let mut web_data = None;
+    let mut change_datas = Vec::new();
+    let mut expect_data = None;
+
+    for block in blocks {
+        match block {
+            TestBlock::Web(data) => web_data = Some(data),
+            TestBlock::Change(data) => change_datas.push(data),
+            TestBlock::Expect(data) => expect_data = Some(data),
+        }
+    }
+
+    let web_data = web_data.ok_or("Test file missing #web block")?;
+    let expect_data = expect_data.ok_or("Test file missing #expect block")?;
+    let expected_output = String::from_utf8_lossy(&expect_data).into_owned();
+
+    let webname = Rc::new("web".to_string());
+    let original_weblines = split_into_filelines(&webname, &web_data);
+    let mut weblines = original_weblines.clone();
+
+    let mut actual_status = 0;
+    let mut failure = None;
+
+    for (n, change_data) in change_datas.iter().enumerate() {
+        let chname = Rc::new(format!("change{}", n + 1));
+        let chlines = split_into_filelines(&chname, change_data);
+
+        let result = select_and_parse_changefile(chlines, false)
+            .and_then(|sections| apply_changefile(weblines.clone(), sections, DEFAULT_FUZZ, verbose));
+
+        match result {
+            Ok(result) => weblines = result,
+            Err(e) => {
+                failure = Some(e.to_string());
+                actual_status = 1;
+                break;
+            }
+        }
+    }
+
+    let actual_output = if failure.is_some() {
+        String::new()
+    } else if let Some(policy) = newline_policy {
+        let mut buf = Vec::new();
+        write_filelines(&mut buf, &weblines, policy)?;
+        String::from_utf8_lossy(&buf).into_owned()
+    } else if diff_mode {
+        String::from_utf8_lossy(&unified_diff(&original_weblines, &weblines, "web")).into_owned()
+    } else {
+        format_filelines(&weblines)
+    };
+
+    if actual_status == expected_status && actual_output == expected_output {
+        if verbose > 0 {
+            eprintln!("Test passed [{}]", path);
+        }
+        return Ok(());
+    }
+
+    eprintln!("Test failed [{}]", path);
+    if actual_status != expected_status {
+        eprintln!(
+            "  #status: expected {}, got {}",
+            expected_status, actual_status
+        );
+    }
+    if actual_output != expected_output {
+        eprintln!("  #expect mismatch:");
+        print_line_diff(&expected_output, &actual_output);
+    }
+    if let Some(msg) = &failure {
+        eprintln!("  error: {}", msg);
+    }
+
+    Err("Test failed".into())
+}
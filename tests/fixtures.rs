@@ -0,0 +1,30 @@
+use std::fs;
+
+use web_ch_file_line::{run, Config};
+
+#[test]
+fn fixtures_pass() {
+    let fixtures_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+    let mut fixtures: Vec<_> = fs::read_dir(fixtures_dir)
+        .expect("failed to read tests/fixtures")
+        .map(|entry| entry.expect("failed to read fixture entry").path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "test"))
+        .collect();
+    fixtures.sort();
+
+    assert!(!fixtures.is_empty(), "no fixtures found in tests/fixtures");
+
+    for path in fixtures {
+        let args = vec![
+            "web_ch_file_line".to_string(),
+            "--test".to_string(),
+            path.to_string_lossy().into_owned(),
+        ];
+
+        let config = Config::new(&args)
+            .unwrap_or_else(|e| panic!("{}: failed to parse args: {}", path.display(), e));
+
+        run(config).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    }
+}